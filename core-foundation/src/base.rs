@@ -0,0 +1,315 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Base CoreFoundation types and the [`TCFType`] trait that every wrapper in this crate
+//! implements.
+
+use std::ffi::c_void;
+use std::fmt;
+use std::mem;
+
+use crate::string::{CFString, CFStringRef};
+use crate::ConcreteCFType;
+
+/// An untyped reference to any CoreFoundation object.
+pub type CFTypeRef = *const c_void;
+
+/// The type of a `CFTypeID`, as returned by e.g. `CFStringGetTypeID`.
+pub type CFTypeID = usize;
+
+/// The type of a `CFHashCode`, as returned by `CFHash`.
+pub type CFHashCode = usize;
+
+/// The type of a `CFIndex`.
+pub type CFIndex = isize;
+
+/// The type of a `CFOptionFlags`.
+pub type CFOptionFlags = usize;
+
+/// CoreFoundation's C `Boolean` type: zero is false, nonzero is true.
+pub type Boolean = u8;
+
+#[repr(C)]
+pub struct __CFAllocator(c_void);
+
+/// A reference to a `CFAllocator`.
+pub type CFAllocatorRef = *const __CFAllocator;
+
+extern "C" {
+    /// The default allocator, suitable for passing to any CoreFoundation `Create`/`Copy`
+    /// function that takes a `CFAllocatorRef`.
+    pub static kCFAllocatorDefault: CFAllocatorRef;
+
+    pub fn CFRetain(cf: CFTypeRef) -> CFTypeRef;
+    pub fn CFRelease(cf: CFTypeRef);
+    pub fn CFEqual(cf1: CFTypeRef, cf2: CFTypeRef) -> Boolean;
+    pub fn CFHash(cf: CFTypeRef) -> CFHashCode;
+    pub fn CFGetTypeID(cf: CFTypeRef) -> CFTypeID;
+    pub fn CFGetRetainCount(cf: CFTypeRef) -> CFIndex;
+    pub fn CFShow(cf: CFTypeRef);
+    pub fn CFCopyDescription(cf: CFTypeRef) -> CFStringRef;
+}
+
+/// A concrete `*const T`-style reference type wrapped by a [`TCFType`] implementation.
+pub trait TCFTypeRef {
+    fn as_void_ptr(&self) -> *const c_void;
+
+    /// # Safety
+    ///
+    /// `ptr` must be a valid instance of the concrete CF type this reference stands for.
+    unsafe fn from_void_ptr(ptr: *const c_void) -> Self;
+}
+
+impl<T> TCFTypeRef for *const T {
+    #[inline]
+    fn as_void_ptr(&self) -> *const c_void {
+        (*self) as *const c_void
+    }
+
+    #[inline]
+    unsafe fn from_void_ptr(ptr: *const c_void) -> Self {
+        ptr as *const T
+    }
+}
+
+/// Converts a Rust value to a `void *` pointer suitable for use as a CoreFoundation "info"
+/// context pointer, e.g. for `CFArrayCallBacks` or `CFMessagePortContext`.
+pub unsafe trait ToVoid<T> {
+    fn to_void(&self) -> *const c_void;
+}
+
+/// All CoreFoundation wrapper types in this crate implement this trait. It provides the
+/// conversions to and from the underlying `Ref` type that the [`declare_TCFType`] and
+/// [`impl_TCFType`] macros rely on; most callers will use the higher-level methods it provides
+/// rather than these directly.
+///
+/// [`declare_TCFType`]: ../macro.declare_TCFType.html
+/// [`impl_TCFType`]: ../macro.impl_TCFType.html
+pub trait TCFType {
+    /// The concrete reference type wrapped by this type, e.g. `CFStringRef`.
+    type Ref: TCFTypeRef;
+
+    /// Returns the object's concrete `Ref`.
+    fn as_concrete_TypeRef(&self) -> Self::Ref;
+
+    /// Returns the object as an untyped `CFTypeRef`.
+    fn as_CFTypeRef(&self) -> CFTypeRef;
+
+    /// Wraps up an instance of the concrete `Ref` type, taking ownership of an existing retain
+    /// on `reference` ("the Create Rule"). The wrapper will release that retain on drop.
+    ///
+    /// Panics if `reference` is null; see [`Self::try_wrap_under_create_rule`] for a
+    /// non-panicking version.
+    ///
+    /// # Safety
+    ///
+    /// `reference` must either be null or a valid, fully constructed instance of the underlying
+    /// CoreFoundation type, and the caller must not otherwise release the retain being handed
+    /// off.
+    unsafe fn wrap_under_create_rule(reference: Self::Ref) -> Self;
+
+    /// Like [`Self::wrap_under_create_rule`], but returns `None` instead of panicking when
+    /// `reference` is null. Most CoreFoundation `Create`/`Copy` APIs return null on failure, so
+    /// this lets callers surface that as a recoverable error rather than aborting the process.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::wrap_under_create_rule`].
+    unsafe fn try_wrap_under_create_rule(reference: Self::Ref) -> Option<Self>
+    where
+        Self: Sized;
+
+    /// Wraps up an instance of the concrete `Ref` type under "the Get Rule": `reference` is
+    /// retained by this call, so the resulting wrapper owns an independent retain.
+    ///
+    /// Panics if `reference` is null; see [`Self::try_wrap_under_get_rule`] for a non-panicking
+    /// version.
+    ///
+    /// # Safety
+    ///
+    /// `reference` must either be null or a valid, fully constructed instance of the underlying
+    /// CoreFoundation type.
+    unsafe fn wrap_under_get_rule(reference: Self::Ref) -> Self;
+
+    /// Like [`Self::wrap_under_get_rule`], but returns `None` instead of panicking when
+    /// `reference` is null.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::wrap_under_get_rule`].
+    unsafe fn try_wrap_under_get_rule(reference: Self::Ref) -> Option<Self>
+    where
+        Self: Sized;
+
+    /// Returns the `CFTypeID` of the underlying CoreFoundation class that this wrapper wraps.
+    fn type_id() -> CFTypeID;
+
+    /// Returns the `CFTypeID` of this particular instance.
+    #[inline]
+    fn type_of(&self) -> CFTypeID {
+        unsafe { CFGetTypeID(self.as_CFTypeRef()) }
+    }
+
+    /// Returns whether this instance's runtime type matches `OtherCFType`'s.
+    #[inline]
+    fn instance_of<OtherCFType: TCFType>(&self) -> bool {
+        self.type_of() == <OtherCFType as TCFType>::type_id()
+    }
+
+    /// Returns `self` as a type-erased [`CFType`], retaining it in the process.
+    #[inline]
+    fn as_CFType(&self) -> CFType {
+        unsafe { CFType::wrap_under_get_rule(self.as_CFTypeRef()) }
+    }
+
+    /// Moves `self` into a type-erased [`CFType`], without adjusting the retain count.
+    #[inline]
+    fn into_CFType(self) -> CFType
+    where
+        Self: Sized,
+    {
+        let reference = self.as_CFTypeRef();
+        mem::forget(self);
+        unsafe { TCFType::wrap_under_create_rule(reference) }
+    }
+}
+
+/// `impl_TCFType!`'s `type_id()` calls its `$ty_id` argument with no arguments, matching the real
+/// per-class `XGetTypeID()` getters (e.g. `CFMessagePortGetTypeID()`) that every *concrete* CF
+/// class publishes. `CFType` type-erases every CF object rather than wrapping one particular
+/// class, so there is no such class-level `CFTypeGetTypeID()` to call — `CFGetTypeID` above takes
+/// an *instance* and returns its runtime type ID, a different thing entirely. This shim exists
+/// purely to give the macro a zero-argument function to call; it deliberately never matches a
+/// real `CFTypeID`, so `instance_of::<CFType>()`/`downcast::<CFType>()` always report
+/// `false`/`None`, which is correct since "this object's exact type is the erased `CFType`" isn't
+/// a meaningful question.
+fn __cf_type_class_type_id() -> CFTypeID {
+    CFTypeID::MAX
+}
+
+declare_TCFType! {
+    /// A CoreFoundation object of any type.
+    ///
+    /// This is useful for holding a heterogeneous collection of CF objects, e.g. as produced by
+    /// some property-list-style APIs, and recovering their concrete type later with
+    /// [`CFType::downcast`].
+    CFType, CFTypeRef
+}
+impl_TCFType!(CFType, CFTypeRef, __cf_type_class_type_id);
+impl_CFHash!(CFType);
+
+impl fmt::Debug for CFType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = unsafe { CFString::wrap_under_create_rule(CFCopyDescription(self.0)) };
+        fmt::Debug::fmt(&desc, f)
+    }
+}
+
+impl CFType {
+    // The `PartialEq` that `impl_TCFType!` generates compares `as_CFType()` values with `.eq()`.
+    // For `CFType` itself that call resolves to this inherent method, which compares with
+    // `CFEqual`, rather than back to `PartialEq::eq`.
+    #[inline]
+    fn eq(&self, other: &CFType) -> bool {
+        unsafe { CFEqual(self.as_CFTypeRef(), other.as_CFTypeRef()) != 0 }
+    }
+
+    /// Attempts to downcast `self` to a concrete `TCFType` subclass, returning a new, retained
+    /// instance of it.
+    ///
+    /// Returns `None` if `self`'s runtime `CFTypeID` does not match `T`'s.
+    pub fn downcast<T: ConcreteCFType>(&self) -> Option<T> {
+        if self.instance_of::<T>() {
+            unsafe { Some(T::wrap_under_get_rule(T::Ref::from_void_ptr(self.as_CFTypeRef()))) }
+        } else {
+            None
+        }
+    }
+
+    /// Like [`Self::downcast`], but borrows `self` instead of retaining a new instance.
+    ///
+    /// Returns `None` if `self`'s runtime `CFTypeID` does not match `T`'s.
+    pub fn downcast_ref<T: ConcreteCFType>(&self) -> Option<&T> {
+        if self.instance_of::<T>() {
+            // `declare_TCFType!` marks concrete (non-generic) wrappers `#[repr(C)]` with their
+            // `Ref` as the sole field, the same as `CFType` itself, so this reinterpretation is
+            // guaranteed layout-compatible rather than relying on `repr(Rust)` happening to agree.
+            Some(unsafe { &*(self as *const CFType as *const T) })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `kCFNull` is a genuine, always-valid CF singleton (distinct from a null pointer), so it's a
+    // convenient concrete object to downcast in these tests without pulling in another module.
+    extern "C" {
+        static kCFNull: CFTypeRef;
+        fn CFNullGetTypeID() -> CFTypeID;
+    }
+
+    declare_TCFType!(TestNull, CFTypeRef);
+    impl_TCFType!(TestNull, CFTypeRef, CFNullGetTypeID);
+
+    extern "C" fn dummy_get_type_id() -> CFTypeID {
+        // Not a real CFTypeID; just needs to differ from `TestNull`'s.
+        CFTypeID::MAX
+    }
+
+    declare_TCFType!(Dummy, CFTypeRef);
+    impl_TCFType!(Dummy, CFTypeRef, dummy_get_type_id);
+
+    #[test]
+    fn downcast_matches_the_concrete_type() {
+        let null_obj = unsafe { TestNull::wrap_under_get_rule(kCFNull) };
+        let cf_type = null_obj.as_CFType();
+        assert!(cf_type.downcast::<TestNull>().is_some());
+        assert!(cf_type.downcast_ref::<TestNull>().is_some());
+    }
+
+    #[test]
+    fn downcast_rejects_a_mismatched_type() {
+        let null_obj = unsafe { TestNull::wrap_under_get_rule(kCFNull) };
+        let cf_type = null_obj.as_CFType();
+        assert!(cf_type.downcast::<Dummy>().is_none());
+        assert!(cf_type.downcast_ref::<Dummy>().is_none());
+    }
+
+    #[test]
+    fn try_wrap_under_create_rule_returns_none_for_null() {
+        assert!(unsafe { TestNull::try_wrap_under_create_rule(std::ptr::null()) }.is_none());
+    }
+
+    #[test]
+    fn try_wrap_under_get_rule_returns_none_for_null() {
+        assert!(unsafe { TestNull::try_wrap_under_get_rule(std::ptr::null()) }.is_none());
+    }
+
+    #[test]
+    fn try_wrap_under_get_rule_returns_some_for_a_valid_reference() {
+        assert!(unsafe { TestNull::try_wrap_under_get_rule(kCFNull) }.is_some());
+    }
+
+    #[test]
+    fn cf_hash_agrees_with_cf_equal_as_a_hashset_key() {
+        use std::collections::HashSet;
+
+        let a = unsafe { TestNull::wrap_under_get_rule(kCFNull) }.as_CFType();
+        let b = unsafe { TestNull::wrap_under_get_rule(kCFNull) }.as_CFType();
+        assert_eq!(a, b);
+
+        let mut set = HashSet::new();
+        set.insert(a);
+        assert!(set.contains(&b));
+    }
+}
@@ -61,6 +61,10 @@ macro_rules! declare_TCFType {
         $ty:ident<$($p:ident $(: $bound:path)*),*>, $raw:ident
     ) => {
         $(#[$doc])*
+        // `repr(C)` pins the `$raw` field to offset 0 so that concrete, non-generic wrappers
+        // (which have no `PhantomData` fields) are layout-compatible with `CFType`, as relied on
+        // by `CFType::downcast_ref`.
+        #[repr(C)]
         pub struct $ty<$($p $(: $bound)*),*>($raw, $(::std::marker::PhantomData<$p>),*);
 
         #[allow(unused_imports)]
@@ -99,9 +103,17 @@ macro_rules! impl_TCFType {
 
             #[inline]
             unsafe fn wrap_under_get_rule(reference: $ty_ref) -> Self {
-                assert!(!reference.is_null(), "Attempted to create a NULL object.");
+                Self::try_wrap_under_get_rule(reference)
+                    .expect("Attempted to create a NULL object.")
+            }
+
+            #[inline]
+            unsafe fn try_wrap_under_get_rule(reference: $ty_ref) -> Option<Self> {
+                if reference.is_null() {
+                    return None;
+                }
                 let reference = $crate::base::CFRetain(reference as *const ::core::ffi::c_void) as $ty_ref;
-                $crate::base::TCFType::wrap_under_create_rule(reference)
+                $crate::base::TCFType::try_wrap_under_create_rule(reference)
             }
 
             #[allow(non_snake_case)]
@@ -112,10 +124,18 @@ macro_rules! impl_TCFType {
 
             #[inline]
             unsafe fn wrap_under_create_rule(reference: $ty_ref) -> Self {
-                assert!(!reference.is_null(), "Attempted to create a NULL object.");
+                Self::try_wrap_under_create_rule(reference)
+                    .expect("Attempted to create a NULL object.")
+            }
+
+            #[inline]
+            unsafe fn try_wrap_under_create_rule(reference: $ty_ref) -> Option<Self> {
+                if reference.is_null() {
+                    return None;
+                }
                 // we need one PhantomData for each type parameter so call ourselves
                 // again with @Phantom $p to produce that
-                $ty(reference $(, impl_TCFType!(@Phantom $p))*)
+                Some($ty(reference $(, impl_TCFType!(@Phantom $p))*))
             }
 
             #[inline]
@@ -237,6 +257,36 @@ macro_rules! impl_CFComparison {
     };
 }
 
+/// Implement `std::hash::Hash` for the given type in terms of [`CFHash`].
+///
+/// CoreFoundation guarantees that two objects considered equal by [`CFEqual`] (and thus by the
+/// `PartialEq`/`Eq` impls generated by [`impl_TCFType`]) produce the same [`CFHash`] value, so
+/// this keeps the `Eq`/`Hash` contract intact and lets wrapper types be used as `HashMap`/
+/// `HashSet` keys.
+///
+/// The type must have an implementation of the [`TCFType`] trait, usually provided using the
+/// [`impl_TCFType`] macro.
+///
+/// [`CFHash`]: https://developer.apple.com/documentation/corefoundation/1521853-cfhash
+/// [`CFEqual`]: https://developer.apple.com/documentation/corefoundation/1520277-cfequal
+/// [`TCFType`]: base/trait.TCFType.html
+/// [`impl_TCFType`]: macro.impl_TCFType.html
+#[macro_export]
+macro_rules! impl_CFHash {
+    ($ty:ident) => {
+        impl_CFHash!($ty<>);
+    };
+    ($ty:ident<$($p:ident $(: $bound:path)*),*>) => {
+        impl<$($p $(: $bound)*),*> ::std::hash::Hash for $ty<$($p),*> {
+            #[inline]
+            fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+                use $crate::base::TCFType;
+                unsafe { $crate::base::CFHash(self.as_CFTypeRef()) }.hash(state)
+            }
+        }
+    };
+}
+
 pub mod array;
 pub mod attributed_string;
 pub mod base;
@@ -249,6 +299,7 @@ pub mod dictionary;
 pub mod error;
 pub mod filedescriptor;
 pub mod mach_port;
+pub mod messageport;
 pub mod number;
 pub mod propertylist;
 pub mod runloop;
@@ -0,0 +1,351 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Two-way, named, local/remote inter-process messaging.
+//!
+//! A [`CFMessagePort`] is either a *local* port, which receives requests through a Rust callback
+//! and is typically turned into a [`CFRunLoopSource`] so it can be driven by a run loop, or a
+//! *remote* port, which is used to send requests (created elsewhere, by name) to a local port.
+
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use crate::base::{kCFAllocatorDefault, Boolean, CFAllocatorRef, CFIndex, CFTypeID, TCFType};
+use crate::data::{CFData, CFDataRef};
+use crate::runloop::{kCFRunLoopDefaultMode, CFRunLoopSource, CFRunLoopSourceRef};
+use crate::string::{CFString, CFStringRef};
+
+#[repr(C)]
+pub struct __CFMessagePort(c_void);
+
+pub type CFMessagePortRef = *const __CFMessagePort;
+
+type CFMessagePortCallBack = extern "C" fn(
+    local: CFMessagePortRef,
+    msgid: i32,
+    data: CFDataRef,
+    info: *mut c_void,
+) -> CFDataRef;
+
+#[repr(C)]
+struct CFMessagePortContext {
+    version: CFIndex,
+    info: *mut c_void,
+    retain: Option<extern "C" fn(info: *const c_void) -> *const c_void>,
+    release: Option<extern "C" fn(info: *const c_void)>,
+    copy_description: Option<extern "C" fn(info: *const c_void) -> CFStringRef>,
+}
+
+extern "C" {
+    fn CFMessagePortCreateLocal(
+        allocator: CFAllocatorRef,
+        name: CFStringRef,
+        callout: CFMessagePortCallBack,
+        context: *mut CFMessagePortContext,
+        should_free_info: *mut Boolean,
+    ) -> CFMessagePortRef;
+
+    fn CFMessagePortCreateRemote(allocator: CFAllocatorRef, name: CFStringRef) -> CFMessagePortRef;
+
+    fn CFMessagePortSendRequest(
+        remote: CFMessagePortRef,
+        msgid: i32,
+        data: CFDataRef,
+        send_timeout: f64,
+        rcv_timeout: f64,
+        reply_mode: CFStringRef,
+        return_data: *mut CFDataRef,
+    ) -> i32;
+
+    fn CFMessagePortCreateRunLoopSource(
+        allocator: CFAllocatorRef,
+        local: CFMessagePortRef,
+        order: CFIndex,
+    ) -> CFRunLoopSourceRef;
+
+    fn CFMessagePortInvalidate(ms: CFMessagePortRef);
+    fn CFMessagePortIsValid(ms: CFMessagePortRef) -> Boolean;
+    fn CFMessagePortGetTypeID() -> CFTypeID;
+}
+
+declare_TCFType!(
+    /// A two-way local/remote messaging port.
+    CFMessagePort,
+    CFMessagePortRef
+);
+impl_TCFType!(CFMessagePort, CFMessagePortRef, CFMessagePortGetTypeID);
+
+/// The outcome of a failed [`CFMessagePort::send_request`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CFMessagePortError {
+    /// The remote port did not accept the request within the send timeout.
+    SendTimeout,
+    /// No reply was received within the receive timeout.
+    ReceiveTimeout,
+    /// The port had already been invalidated.
+    PortIsInvalid,
+    /// The underlying Mach transport reported an error.
+    TransportError,
+    /// The port became invalid while the request was in flight.
+    BecameInvalid,
+    /// A status code not recognized by this wrapper.
+    Unknown(i32),
+}
+
+impl From<i32> for CFMessagePortError {
+    fn from(status: i32) -> CFMessagePortError {
+        match status {
+            -1 => CFMessagePortError::SendTimeout,
+            -2 => CFMessagePortError::ReceiveTimeout,
+            -3 => CFMessagePortError::PortIsInvalid,
+            -4 => CFMessagePortError::TransportError,
+            -5 => CFMessagePortError::BecameInvalid,
+            status => CFMessagePortError::Unknown(status),
+        }
+    }
+}
+
+extern "C" fn release_info<F>(info: *const c_void) {
+    drop(unsafe { Box::from_raw(info as *mut F) });
+}
+
+extern "C" fn message_port_callout<F>(
+    _local: CFMessagePortRef,
+    msgid: i32,
+    data: CFDataRef,
+    info: *mut c_void,
+) -> CFDataRef
+where
+    F: FnMut(i32, CFData) -> Option<CFData>,
+{
+    let callback = unsafe { &mut *(info as *mut F) };
+    let data = unsafe { CFData::wrap_under_get_rule(data) };
+    match callback(msgid, data) {
+        Some(reply) => {
+            // Transfer our +1 retain on `reply` to the caller, per the CF create rule: forget
+            // the wrapper so its `Drop` doesn't release the retain we're handing off.
+            let reference = reply.as_concrete_TypeRef();
+            mem::forget(reply);
+            reference
+        }
+        None => ptr::null(),
+    }
+}
+
+impl CFMessagePort {
+    /// Creates a local (server) message port under `name`, invoking `callback` with the message
+    /// ID and payload of every incoming request. The `CFData` returned by `callback`, if any, is
+    /// sent back to the requester as the reply.
+    ///
+    /// Returns `None` if a port named `name` could not be created, e.g. because the name is
+    /// already in use.
+    pub fn create_local<F>(name: &CFString, callback: F) -> Option<CFMessagePort>
+    where
+        F: FnMut(i32, CFData) -> Option<CFData> + 'static,
+    {
+        let info = Box::into_raw(Box::new(callback));
+        let mut context = CFMessagePortContext {
+            version: 0,
+            info: info as *mut c_void,
+            retain: None,
+            release: Some(release_info::<F>),
+            copy_description: None,
+        };
+        let mut should_free_info: Boolean = 0;
+
+        unsafe {
+            let port_ref = CFMessagePortCreateLocal(
+                kCFAllocatorDefault,
+                name.as_concrete_TypeRef(),
+                message_port_callout::<F>,
+                &mut context,
+                &mut should_free_info,
+            );
+
+            if port_ref.is_null() {
+                // On failure CF itself invokes `context.release` (our `release_info::<F>`)
+                // before returning, unless `should_free_info` tells us it didn't — so only free
+                // `info` ourselves in that case, to avoid double-freeing the box.
+                if should_free_info != 0 {
+                    drop(Box::from_raw(info));
+                }
+                return None;
+            }
+            // On success CF retains `info` for as long as the port lives and will release it via
+            // `context.release` when the port is invalidated/deallocated; we must not free it.
+            Some(TCFType::wrap_under_create_rule(port_ref))
+        }
+    }
+
+    /// Creates a remote port for sending requests to the local port previously registered under
+    /// `name`, possibly in another process. Returns `None` if no such port is currently
+    /// registered.
+    pub fn create_remote(name: &CFString) -> Option<CFMessagePort> {
+        unsafe {
+            let port_ref = CFMessagePortCreateRemote(kCFAllocatorDefault, name.as_concrete_TypeRef());
+            if port_ref.is_null() {
+                None
+            } else {
+                Some(TCFType::wrap_under_create_rule(port_ref))
+            }
+        }
+    }
+
+    /// Sends `data` to this (remote) port as a request tagged with `msgid`, waiting up to
+    /// `send_timeout` for the request to be accepted and up to `receive_timeout` for a reply.
+    ///
+    /// Returns the reply payload, or `None` if the remote end sent no reply.
+    pub fn send_request(
+        &self,
+        msgid: i32,
+        data: &CFData,
+        send_timeout: Duration,
+        receive_timeout: Duration,
+    ) -> Result<Option<CFData>, CFMessagePortError> {
+        let mut reply: CFDataRef = ptr::null();
+        let status = unsafe {
+            CFMessagePortSendRequest(
+                self.as_concrete_TypeRef(),
+                msgid,
+                data.as_concrete_TypeRef(),
+                send_timeout.as_secs_f64(),
+                receive_timeout.as_secs_f64(),
+                kCFRunLoopDefaultMode,
+                &mut reply,
+            )
+        };
+
+        if status != 0 {
+            return Err(CFMessagePortError::from(status));
+        }
+        if reply.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(unsafe { TCFType::wrap_under_create_rule(reply) }))
+        }
+    }
+
+    /// Wraps this port in a [`CFRunLoopSource`] so it can be added to a run loop and have its
+    /// callback (for local ports) driven by that run loop.
+    pub fn create_run_loop_source(&self, order: CFIndex) -> Option<CFRunLoopSource> {
+        unsafe {
+            let source_ref =
+                CFMessagePortCreateRunLoopSource(kCFAllocatorDefault, self.as_concrete_TypeRef(), order);
+            if source_ref.is_null() {
+                None
+            } else {
+                Some(TCFType::wrap_under_create_rule(source_ref))
+            }
+        }
+    }
+
+    /// Returns whether this port is still valid, i.e. has not been invalidated.
+    pub fn is_valid(&self) -> bool {
+        unsafe { CFMessagePortIsValid(self.as_concrete_TypeRef()) != 0 }
+    }
+
+    /// Invalidates this port, so it can no longer send or receive requests.
+    pub fn invalidate(&self) {
+        unsafe { CFMessagePortInvalidate(self.as_concrete_TypeRef()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn message_port_error_from_maps_known_and_unknown_status_codes() {
+        assert_eq!(CFMessagePortError::from(-1), CFMessagePortError::SendTimeout);
+        assert_eq!(CFMessagePortError::from(-2), CFMessagePortError::ReceiveTimeout);
+        assert_eq!(CFMessagePortError::from(-3), CFMessagePortError::PortIsInvalid);
+        assert_eq!(CFMessagePortError::from(-4), CFMessagePortError::TransportError);
+        assert_eq!(CFMessagePortError::from(-5), CFMessagePortError::BecameInvalid);
+        assert_eq!(CFMessagePortError::from(42), CFMessagePortError::Unknown(42));
+    }
+
+    // The closure type the trampoline functions below are instantiated with in these tests.
+    // Boxing it lets us name `F` so we can call `release_info::<F>`/`message_port_callout::<F>`
+    // directly, the same way `base.rs`'s test module exercises `TestNull`/`kCFNull` without a
+    // live run loop or a real `CFMessagePortCreateLocal` call.
+    type Callback = Box<dyn FnMut(i32, CFData) -> Option<CFData>>;
+
+    fn info_from(callback: Callback) -> *mut c_void {
+        Box::into_raw(Box::new(callback)) as *mut c_void
+    }
+
+    fn make_data(bytes: &[u8]) -> CFData {
+        extern "C" {
+            fn CFDataCreate(allocator: CFAllocatorRef, bytes: *const u8, length: CFIndex) -> CFDataRef;
+        }
+        unsafe {
+            let data_ref = CFDataCreate(kCFAllocatorDefault, bytes.as_ptr(), bytes.len() as CFIndex);
+            TCFType::wrap_under_create_rule(data_ref)
+        }
+    }
+
+    #[test]
+    fn release_info_drops_the_boxed_callback() {
+        struct DropFlag(Rc<Cell<bool>>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(false));
+        let flag = DropFlag(Rc::clone(&dropped));
+        let callback: Callback = Box::new(move |_msgid, _data| {
+            let _keep_alive = &flag;
+            None
+        });
+
+        release_info::<Callback>(info_from(callback) as *const c_void);
+
+        assert!(dropped.get());
+    }
+
+    #[test]
+    fn callout_forgets_the_reply_instead_of_releasing_it() {
+        let callback: Callback = Box::new(|_msgid, data| Some(data));
+        let info = info_from(callback);
+        let input = make_data(&[1, 2, 3]);
+
+        let reply_ref =
+            message_port_callout::<Callback>(ptr::null(), 0, input.as_concrete_TypeRef(), info);
+
+        // `reply_ref` is the same object that `input` holds. If the callout had released its
+        // retain on `reply` before returning, reclaiming that retain below would release a
+        // retain nobody owns, and dropping `input` would then free the object a second time.
+        // Reclaim the retain the callout handed off, exactly as CF would, and let it drop cleanly.
+        assert!(!reply_ref.is_null());
+        drop(unsafe { CFData::wrap_under_create_rule(reply_ref) });
+
+        release_info::<Callback>(info as *const c_void);
+    }
+
+    #[test]
+    fn callout_returns_null_when_callback_declines_to_reply() {
+        let callback: Callback = Box::new(|_msgid, _data| None);
+        let info = info_from(callback);
+        let input = make_data(&[]);
+
+        let reply_ref =
+            message_port_callout::<Callback>(ptr::null(), 0, input.as_concrete_TypeRef(), info);
+
+        assert!(reply_ref.is_null());
+
+        release_info::<Callback>(info as *const c_void);
+    }
+}
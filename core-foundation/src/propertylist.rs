@@ -0,0 +1,47 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Property lists: the heterogeneous, `CFString`/`CFNumber`/`CFBoolean`/`CFData`/`CFArray`/
+//! `CFDictionary`/`CFDate` tree structure produced by serialization APIs such as
+//! `CFPropertyListCreateWithData`.
+
+use crate::base::{CFTypeID, CFTypeRef, TCFType};
+use crate::ConcreteCFType;
+
+pub type CFPropertyListRef = CFTypeRef;
+
+declare_TCFType! {
+    /// A node in a decoded property-list tree. Concretely a `CFString`, `CFNumber`, `CFBoolean`,
+    /// `CFData`, `CFArray`, `CFDictionary`, or `CFDate`; use [`CFPropertyList::downcast`] to
+    /// recover the concrete type.
+    CFPropertyList, CFPropertyListRef
+}
+impl_TCFType!(CFPropertyList, CFPropertyListRef, CFPropertyListGetTypeID);
+impl_CFTypeDescription!(CFPropertyList);
+
+extern "C" {
+    fn CFPropertyListGetTypeID() -> CFTypeID;
+}
+
+impl CFPropertyList {
+    /// Attempts to downcast this property-list node to a concrete `TCFType` subclass, returning
+    /// a new, retained instance of it.
+    ///
+    /// Returns `None` if the node's runtime `CFTypeID` does not match `T`'s, e.g. because the
+    /// node is a `CFDictionary` and `T` is `CFString`.
+    pub fn downcast<T: ConcreteCFType>(&self) -> Option<T> {
+        self.as_CFType().downcast()
+    }
+}
+
+impl<T: TCFType> From<T> for CFPropertyList {
+    fn from(t: T) -> CFPropertyList {
+        unsafe { CFPropertyList::wrap_under_get_rule(t.as_CFTypeRef()) }
+    }
+}